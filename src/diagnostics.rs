@@ -0,0 +1,122 @@
+// Structured error reporting for `find_lifetimes`, in the spirit of rustc's
+// standardized JSON error output: a message plus one or more spans, so
+// editors and tooling can consume the errors without scraping text.
+
+/// A location in the input, as 0-based line/column indices.
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+	pub line: usize,
+	pub column: usize,
+}
+
+/// A single error found while parsing lifetime annotations, with an
+/// optional secondary span (e.g. pointing back at where a lifetime was
+/// originally opened).
+#[derive(Debug)]
+pub struct Diagnostic {
+	pub span: Span,
+	pub message: String,
+	pub secondary: Option<(Span, String)>,
+}
+
+/// Prints `file:line:col: error: message` to stderr, rustc-style, followed
+/// by a `note:` line for the secondary span if there is one.
+pub fn print_human(file: &str, diagnostics: &[Diagnostic]) {
+	for d in diagnostics {
+		eprintln!("{}:{}:{}: error: {}", file, d.span.line + 1, d.span.column + 1, d.message);
+
+		if let Some((span, message)) = &d.secondary {
+			eprintln!("{}:{}:{}: note: {}", file, span.line + 1, span.column + 1, message);
+		}
+	}
+}
+
+/// Serializes the diagnostics as a JSON array, written by hand rather than
+/// through serde.
+pub fn to_json(file: &str, diagnostics: &[Diagnostic]) -> String {
+	let mut out = String::from("[");
+
+	for (i, d) in diagnostics.iter().enumerate() {
+		if i > 0 {
+			out.push(',');
+		}
+
+		out.push_str(&format!(
+			r#"{{"file":{},"line":{},"column":{},"message":{}"#,
+			json_string(file), d.span.line + 1, d.span.column + 1, json_string(&d.message)
+		));
+
+		if let Some((span, message)) = &d.secondary {
+			out.push_str(&format!(
+				r#","secondary":{{"file":{},"line":{},"column":{},"message":{}}}"#,
+				json_string(file), span.line + 1, span.column + 1, json_string(message)
+			));
+		}
+
+		out.push('}');
+	}
+
+	out.push(']');
+	out
+}
+
+fn json_string(s: &str) -> String {
+	let mut out = String::with_capacity(s.len() + 2);
+	out.push('"');
+	for c in s.chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\t' => out.push_str("\\t"),
+			c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+			c => out.push(c),
+		}
+	}
+	out.push('"');
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn json_string_escapes_quotes_and_backslashes() {
+		assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+	}
+
+	#[test]
+	fn json_string_escapes_control_characters() {
+		assert_eq!(json_string("a\nb\tc"), "\"a\\nb\\tc\"");
+	}
+
+	#[test]
+	fn to_json_reports_one_based_line_and_column() {
+		let diagnostics = vec![Diagnostic {
+			span: Span { line: 2, column: 5 },
+			message: "oops".to_string(),
+			secondary: None,
+		}];
+
+		let json = to_json("<stdin>", &diagnostics);
+
+		assert!(json.contains("\"line\":3"));
+		assert!(json.contains("\"column\":6"));
+		assert!(!json.contains("secondary"));
+	}
+
+	#[test]
+	fn to_json_includes_secondary_span() {
+		let diagnostics = vec![Diagnostic {
+			span: Span { line: 4, column: 0 },
+			message: "not finished before it is started again".to_string(),
+			secondary: Some((Span { line: 0, column: 16 }, "originally opened here".to_string())),
+		}];
+
+		let json = to_json("<stdin>", &diagnostics);
+
+		assert!(json.contains("\"secondary\":{"));
+		assert!(json.contains("\"line\":1,\"column\":17"));
+	}
+}
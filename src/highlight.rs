@@ -0,0 +1,356 @@
+// A small hand-rolled syntax highlighter for the Rust code shown in the
+// diagram, in the spirit of rustdoc's `highlight.rs`. It tokenizes the
+// whole joined source rather than line-by-line, because some tokens -
+// block comments and string literals - can span multiple lines.
+//
+// Must run after `find_lifetimes` has stripped the annotation comments,
+// since the trailing `// ----\` markers are annotations rather than part
+// of the code, and would otherwise show up as highlighted comments.
+
+use escape::Escape;
+
+static KEYWORDS: &[&str] = &[
+	"as", "async", "await", "box", "break", "const", "continue", "crate",
+	"dyn", "else", "enum", "extern", "false", "fn", "for", "if", "impl",
+	"in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+	"return", "self", "Self", "static", "struct", "super", "trait", "true",
+	"try", "type", "union", "unsafe", "use", "where", "while", "yield",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Class {
+	Keyword,
+	Ident,
+	String,
+	Number,
+	Comment,
+	Lifetime,
+	Op,
+	Macro,
+	Plain,
+}
+
+impl Class {
+	fn css_class(self) -> Option<&'static str> {
+		match self {
+			Class::Keyword => Some("kw"),
+			Class::Ident => Some("ident"),
+			Class::String => Some("string"),
+			Class::Number => Some("number"),
+			Class::Comment => Some("comment"),
+			Class::Lifetime => Some("lifetime"),
+			Class::Op => Some("op"),
+			Class::Macro => Some("macro"),
+			Class::Plain => None,
+		}
+	}
+}
+
+// Lexer state that needs to survive from one line to the next.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+	Normal,
+	BlockComment(u32), // nesting depth, since Rust block comments nest
+}
+
+/// Highlights `code` and returns, for each line, a string of `<tspan>`
+/// elements (and escaped plain text) ready to be inserted into the line's
+/// `<text class="code">` node.
+pub fn highlight_lines(code: &[String]) -> Vec<String> {
+	let joined = code.join("\n");
+	let tokens = tokenize(&joined);
+
+	let mut lines = vec![String::new(); code.len()];
+	let mut line = 0;
+
+	for (class, text) in tokens {
+		for (i, part) in text.split('\n').enumerate() {
+			if i > 0 {
+				line += 1;
+			}
+			if part.is_empty() {
+				continue;
+			}
+			match class.css_class() {
+				Some(css) => lines[line].push_str(&format!(r#"<tspan class="{}">{}</tspan>"#, css, Escape(part))),
+				None => lines[line].push_str(&format!("{}", Escape(part))),
+			}
+		}
+	}
+
+	lines
+}
+
+// If `chars[i]` starts a raw string literal (`r"`, `r#"`, `r##"`, ...),
+// returns the number of `#`s between the `r` and the opening `"`.
+fn raw_string_hashes(chars: &[char], i: usize) -> Option<usize> {
+	let mut hashes = 0;
+	while chars.get(i + 1 + hashes) == Some(&'#') {
+		hashes += 1;
+	}
+	if chars.get(i + 1 + hashes) == Some(&'"') {
+		Some(hashes)
+	} else {
+		None
+	}
+}
+
+fn tokenize(src: &str) -> Vec<(Class, String)> {
+	let chars: Vec<char> = src.chars().collect();
+	let mut i = 0;
+	let mut state = State::Normal;
+	let mut tokens = Vec::new();
+
+	while i < chars.len() {
+		if let State::BlockComment(mut depth) = state {
+			let start = i;
+			while i < chars.len() && depth > 0 {
+				if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+					depth += 1;
+					i += 2;
+				} else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+					depth -= 1;
+					i += 2;
+				} else {
+					i += 1;
+				}
+			}
+			tokens.push((Class::Comment, chars[start..i].iter().collect()));
+			state = if depth == 0 { State::Normal } else { State::BlockComment(depth) };
+			continue;
+		}
+
+		let c = chars[i];
+
+		if c == '/' && chars.get(i + 1) == Some(&'/') {
+			let start = i;
+			while i < chars.len() && chars[i] != '\n' {
+				i += 1;
+			}
+			tokens.push((Class::Comment, chars[start..i].iter().collect()));
+		} else if c == '/' && chars.get(i + 1) == Some(&'*') {
+			state = State::BlockComment(1);
+			tokens.push((Class::Comment, "/*".to_string()));
+			i += 2;
+		} else if c == '"' {
+			let start = i;
+			i += 1;
+			while i < chars.len() && chars[i] != '"' {
+				if chars[i] == '\\' && i + 1 < chars.len() {
+					i += 2;
+				} else {
+					i += 1;
+				}
+			}
+			if i < chars.len() {
+				i += 1;
+			}
+			tokens.push((Class::String, chars[start..i].iter().collect()));
+		} else if c == '\'' {
+			let start = i;
+			if chars.get(i + 1) == Some(&'\\') {
+				// Escaped char literal, e.g. '\n', '\'', '\\'.
+				i += 2;
+				if i < chars.len() {
+					i += 1;
+				}
+				if chars.get(i) == Some(&'\'') {
+					i += 1;
+				}
+				tokens.push((Class::String, chars[start..i].iter().collect()));
+			} else if chars.get(i + 1).is_some_and(|&c| c != '\'') && chars.get(i + 2) == Some(&'\'') {
+				// Plain char literal, e.g. 'a'.
+				i += 3;
+				tokens.push((Class::String, chars[start..i].iter().collect()));
+			} else {
+				// Lifetime, e.g. 'a, 'static.
+				i += 1;
+				while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+					i += 1;
+				}
+				tokens.push((Class::Lifetime, chars[start..i].iter().collect()));
+			}
+		} else if c == 'r' && raw_string_hashes(&chars, i).is_some() {
+			// Raw string, e.g. r"..." or r#"...contains "quotes"..."#. Runs
+			// until the closing `"` followed by the same number of `#`s, so
+			// embedded quotes (and newlines) don't end the token early.
+			let hashes = raw_string_hashes(&chars, i).unwrap();
+			let start = i;
+			i += 2 + hashes;
+
+			loop {
+				if i >= chars.len() {
+					break;
+				}
+				if chars[i] == '"' {
+					let mut end = i + 1;
+					let mut matched = 0;
+					while matched < hashes && chars.get(end) == Some(&'#') {
+						matched += 1;
+						end += 1;
+					}
+					if matched == hashes {
+						i = end;
+						break;
+					}
+				}
+				i += 1;
+			}
+
+			tokens.push((Class::String, chars[start..i].iter().collect()));
+		} else if c.is_ascii_digit() {
+			let start = i;
+
+			if c == '0' && matches!(chars.get(i + 1), Some('x') | Some('X') | Some('b') | Some('B') | Some('o') | Some('O')) {
+				// Hex/binary/octal integer literal, e.g. 0x1F, 0b101, 0o17.
+				i += 2;
+				while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+					i += 1;
+				}
+			} else {
+				// Decimal integer part.
+				while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '_') {
+					i += 1;
+				}
+
+				// A single fractional part, e.g. `1.5` - but not `1.` followed
+				// by another `.` (a range like `0..10`) or an identifier (a
+				// method call like `1.0.to_string()`), since in both of those
+				// cases the `.` isn't part of the literal.
+				if chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+					i += 1;
+					while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '_') {
+						i += 1;
+					}
+				}
+
+				// Optional exponent, e.g. `1e10`, `1.5e-3`.
+				if matches!(chars.get(i), Some('e') | Some('E')) {
+					let mut j = i + 1;
+					if matches!(chars.get(j), Some('+') | Some('-')) {
+						j += 1;
+					}
+					if chars.get(j).is_some_and(|c| c.is_ascii_digit()) {
+						i = j;
+						while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '_') {
+							i += 1;
+						}
+					}
+				}
+			}
+
+			// Optional bounded numeric type suffix, e.g. `42u32`, `1.0f64` -
+			// only consumed as a whole word, so it doesn't eat into a
+			// following identifier like the `to_string` in `1.0.to_string()`.
+			const SUFFIXES: &[&str] = &[
+				"u8", "u16", "u32", "u64", "u128", "usize",
+				"i8", "i16", "i32", "i64", "i128", "isize",
+				"f32", "f64",
+			];
+			for suffix in SUFFIXES {
+				let end = i + suffix.chars().count();
+				if end <= chars.len()
+					&& chars[i..end].iter().collect::<String>() == *suffix
+					&& !chars.get(end).is_some_and(|c| c.is_alphanumeric() || *c == '_')
+				{
+					i = end;
+					break;
+				}
+			}
+
+			tokens.push((Class::Number, chars[start..i].iter().collect()));
+		} else if c.is_alphabetic() || c == '_' {
+			let start = i;
+			while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+				i += 1;
+			}
+			let word: String = chars[start..i].iter().collect();
+			if chars.get(i) == Some(&'!') {
+				i += 1;
+				tokens.push((Class::Macro, format!("{}!", word)));
+			} else if KEYWORDS.contains(&word.as_str()) {
+				tokens.push((Class::Keyword, word));
+			} else {
+				tokens.push((Class::Ident, word));
+			}
+		} else if "+-*/%=<>!&|^~".contains(c) {
+			let start = i;
+			i += 1;
+			tokens.push((Class::Op, chars[start..i].iter().collect()));
+		} else {
+			tokens.push((Class::Plain, c.to_string()));
+			i += 1;
+		}
+	}
+
+	tokens
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn lines_of(code: &[&str]) -> Vec<String> {
+		highlight_lines(&code.iter().map(|s| s.to_string()).collect())
+	}
+
+	#[test]
+	fn block_comment_spans_lines() {
+		let lines = lines_of(&["/* start", "middle", "end */ let x = 1;"]);
+		assert_eq!(lines.len(), 3);
+		assert!(lines[0].contains("<tspan class=\"comment\">"));
+		assert!(lines[0].contains("start"));
+		assert_eq!(lines[1], "<tspan class=\"comment\">middle</tspan>");
+		assert!(lines[2].contains("<tspan class=\"comment\">end */</tspan>"));
+		assert!(lines[2].contains("<tspan class=\"kw\">let</tspan>"));
+	}
+
+	#[test]
+	fn raw_string_with_embedded_quotes_is_one_token() {
+		// Built from an escaped literal (rather than a raw literal) to avoid
+		// fighting with the test source's own quoting.
+		let line = format!("let s = {};", "r#\"it's a \"quoted\" word\"#");
+		let lines = lines_of(&[&line]);
+		assert_eq!(lines.len(), 1);
+		assert_eq!(lines[0].matches("<tspan class=\"string\">").count(), 1);
+	}
+
+	#[test]
+	fn plain_raw_string_with_no_hashes() {
+		let line = "let s = r\"no hashes here\";";
+		let lines = lines_of(&[line]);
+		assert_eq!(lines.len(), 1);
+		assert_eq!(lines[0].matches("<tspan class=\"string\">").count(), 1);
+	}
+
+	#[test]
+	fn lifetime_is_distinguished_from_char_literal() {
+		let lines = lines_of(&["fn f<'a>(c: char) -> bool { c == 'x' }"]);
+		assert_eq!(lines.len(), 1);
+		assert!(lines[0].contains("<tspan class=\"lifetime\">'a</tspan>"));
+		assert!(lines[0].contains("<tspan class=\"string\">'x'</tspan>"));
+	}
+
+	#[test]
+	fn float_literal_does_not_swallow_the_following_method_call() {
+		let lines = lines_of(&["let s = 1.0.to_string();"]);
+		assert_eq!(lines.len(), 1);
+		assert!(lines[0].contains("<tspan class=\"number\">1.0</tspan>"));
+		assert!(lines[0].contains("<tspan class=\"ident\">to_string</tspan>"));
+	}
+
+	#[test]
+	fn integer_literal_does_not_swallow_a_following_range_operator() {
+		let lines = lines_of(&["for i in 0..10 {}"]);
+		assert_eq!(lines.len(), 1);
+		assert!(lines[0].contains("<tspan class=\"number\">0</tspan>"));
+		assert!(lines[0].contains("<tspan class=\"number\">10</tspan>"));
+	}
+
+	#[test]
+	fn numeric_literal_keeps_its_bounded_type_suffix() {
+		let lines = lines_of(&["let x = 42u32;"]);
+		assert_eq!(lines.len(), 1);
+		assert!(lines[0].contains("<tspan class=\"number\">42u32</tspan>"));
+	}
+}
@@ -23,25 +23,49 @@
 extern crate lazy_static;
 extern crate regex;
 extern crate svg;
-extern crate markdown;
+extern crate pulldown_cmark;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
 
 use std::io::{self, Read};
 use regex::Regex;
 use std::collections::HashMap;
 
+mod diagnostics;
 mod escape;
+mod highlight;
+mod theme;
 
+use diagnostics::{Diagnostic, Span};
 use escape::*;
+use highlight::highlight_lines;
+use theme::Theme;
+
+// An unfinished lifetime, tracked along with where it was opened so that a
+// mismatched restart can point back at the original line and column.
+struct UnfinishedLifetime {
+	starting_line: usize,
+	starting_column: usize,
+	comment: String,
+}
 
 // Finds the lifetime annotations and removes the annotations from the code.
-fn find_lifetimes(code: &mut Vec<String>) -> Vec<Lifetime> {
+// Collects every restart-before-close and close-without-open annotation as a
+// `Diagnostic` rather than panicking, so a single run can report all of them
+// instead of aborting on the first. A lifetime still open at the end of the
+// input is not reported.
+fn find_lifetimes(code: &mut [String]) -> Result<Vec<Lifetime>, Vec<Diagnostic>> {
 
 	lazy_static! {
 		static ref RE: Regex = Regex::new(r"^(.*)// (-+)([/\\]) ?(.*)$").unwrap();
 	}
 
-	let mut unfinished_lifetimes: HashMap<usize, Lifetime> = HashMap::new();
+	let mut unfinished_lifetimes: HashMap<usize, UnfinishedLifetime> = HashMap::new();
 	let mut lifetimes: Vec<Lifetime> = Vec::new();
+	let mut diagnostics: Vec<Diagnostic> = Vec::new();
 
 	// Find all the lifetimes.
 	for i in 0..code.len() {
@@ -52,24 +76,41 @@ fn find_lifetimes(code: &mut Vec<String>) -> Vec<Lifetime> {
 			let dash_count = captures[2].len();
 			let is_start = &captures[3] == r"\";
 			let comment = &captures[4];
-			
+			let marker = captures.get(2).unwrap();
+			let column = code[i][..marker.start()].chars().count();
+
 			if unfinished_lifetimes.contains_key(&dash_count) {
 				if is_start {
-					panic!("Lifetime from line {} not finished before it is started again on line {}", unfinished_lifetimes[&dash_count].starting_line, i);
+					let previous = &unfinished_lifetimes[&dash_count];
+					diagnostics.push(Diagnostic {
+						span: Span { line: i, column },
+						message: format!("lifetime with {} dashes not finished before it is started again", dash_count),
+						secondary: Some((
+							Span { line: previous.starting_line, column: previous.starting_column },
+							"lifetime was originally opened here".to_string(),
+						)),
+					});
 				} else {
-					let mut lt = unfinished_lifetimes.remove(&dash_count).unwrap();
-					lt.ending_line = i;
-					lifetimes.push(lt);
+					let previous = unfinished_lifetimes.remove(&dash_count).unwrap();
+					lifetimes.push(Lifetime {
+						starting_line: previous.starting_line,
+						ending_line: i,
+						comment: previous.comment,
+					});
 				}
 			} else {
 				if is_start {
-					unfinished_lifetimes.insert(dash_count, Lifetime {
+					unfinished_lifetimes.insert(dash_count, UnfinishedLifetime {
 						starting_line: i,
-						ending_line: 0,
+						starting_column: column,
 						comment: comment.to_string(),
 					});
 				} else {
-					panic!("Ending lifetime on line {} wasn't started", i);
+					diagnostics.push(Diagnostic {
+						span: Span { line: i, column },
+						message: format!("ending lifetime with {} dashes wasn't started", dash_count),
+						secondary: None,
+					});
 				}
 			}
 
@@ -81,78 +122,78 @@ fn find_lifetimes(code: &mut Vec<String>) -> Vec<Lifetime> {
 		}
 	}
 
-	lifetimes
+	if diagnostics.is_empty() {
+		Ok(lifetimes)
+	} else {
+		Err(diagnostics)
+	}
 }
 
 // Generate an SVG. `code` should have lifetime comments removed.
-fn generate_svg(code: &Vec<String>, lifetimes: &Vec<Lifetime>) -> String {
+fn generate_svg(code: &[String], lifetimes: &[Lifetime], theme: &Theme) -> String {
 	use svg::Document;
 	use svg::node::element::Text;
 	use svg::node::element::Definitions;
 	use svg::node::element::Style;
 	use svg::node::element::Path;
+	use svg::node::element::Rectangle;
 	use svg::node::element::path::Data;
 	use svg::Node;
 
 
-	let mut document = Document::new(); //.set("viewBox", (0, 0, 200, 200));
+	// Rough per-character width (px) for the 16px monospace code font, used
+	// only to size the viewBox - real text width depends on the renderer's
+	// font metrics, which this crate doesn't measure itself.
+	const CHAR_WIDTH: f64 = 9.6;
 
-	// Set up styles. This could be done in a separate CSS file too.
+	let code_width = code.iter()
+		.map(|line| line.chars().count())
+		.max()
+		.unwrap_or(0) as f64 * CHAR_WIDTH;
 
-	let css = r#"<![CDATA[
+	let annotations_width = lifetimes.iter().enumerate()
+		.map(|(i, lifetime)| {
+			let xm = 230 + 20 * i as i32;
+			xm as f64 + 10.0 + lifetime.comment.chars().count() as f64 * CHAR_WIDTH
+		})
+		.fold(0.0_f64, f64::max);
 
-.code {
-	font-family: monospace;
-	font-size: 16;
-	white-space: pre;
-	tab-size: 4;
-}
+	let width = (code_width.max(annotations_width) + 40.0) as i32;
+	let height = (code.len() as f64 * 20.0 + 40.0) as i32;
 
-.annotation {
-	font-size: 16;
-}
+	let mut document = Document::new()
+						.set("viewBox", (0, 0, width, height))
+						.set("width", width)
+						.set("height", height);
 
-.m_code {
-	font-family: monospace;
-}
-
-.m_italic {
-	font-style: italic;
-}
-
-.m_underline {
-	text-decoration: underline;
-}
+	// Set up styles. This could be done in a separate CSS file too.
 
-.m_bold {
-	font-weight: bold;
-}
+	let defs = Definitions::new().add(Style::new(theme.to_css()));
 
-.line {
-	fill: none;
-	stroke: black;
-	stroke-width: 2;
-	stroke-linecap: round;
-	stroke-linejoin: round;
-}
+	document.append(defs);
 
-]]>"#;
+	// A full-bleed background rect, so dark themes don't render on a
+	// transparent (effectively white) canvas.
+	let background = Rectangle::new()
+						.set("x", 0)
+						.set("y", 0)
+						.set("width", "100%")
+						.set("height", "100%")
+						.set("class", "background");
 
-	let defs = Definitions::new().add(Style::new(css));
+	document.append(background);
 
-	document.append(defs);
+	// Draw the code, syntax-highlighted into per-token tspans.
+	let highlighted = highlight_lines(code);
 
-	// Draw the code.
-	for i in 0..code.len() {
+	for (i, line) in highlighted.iter().enumerate() {
 		// Empty lines are included to make text selection nicer.
 
-		// I should probably use tspan elements.
-
 		let text = Text::new()
 						.set("x", 0)
 						.set("y", i*20 + 20)
 						.set("class", "code")
-						.add(svg::node::Text::new(format!("{}", Escape(&code[i]))));
+						.add(svg::node::Text::new(line.clone()));
 
 		document.append(text);
 	}
@@ -204,75 +245,118 @@ fn generate_svg(code: &Vec<String>, lifetimes: &Vec<Lifetime>) -> String {
 	format!("{}", document)
 }
 
-// Convert simple markdown-ish markup to an SVG Text element contents, using <tspan> elements.
-// Only _ (underline), * (bold), / (italic) and ` (code) are supported. Also standard backslash escaping. For example:
+// Convert Markdown annotation markup to an SVG Text element contents, using <tspan> elements.
+// Driven by a pulldown-cmark inline parser, so standard CommonMark emphasis (`**bold**` / `*italic*` or `_italic_`),
+// inline code (`` `code` ``) and links are supported, instead of the previous ad-hoc toggle parser. For example:
 //
-//  *Hello* `world!`
+//  **Hello** `world!`
 //
 // is converted to
 //
 //  <tspan class="m_bold">Hello</tspan> <tspan class="m_code">world!</tspan>
-//
-// And
-//
-//     *foo _bar* baz_
-//
-// Is converted to
-//
-// <tspan class="m_bold">foo </tspan><tspan class="m_bold m_underline">bar</tspan><tspan class="m_underline"> baz</tspan
+// A zero-width guard character prepended to the markup before parsing. An
+// annotation comment is a single inline span, not a multi-block document,
+// but CommonMark still treats `#`, `-`, `*`, `>`, digit-dot etc. as block
+// syntax when they're the first non-whitespace character on a line, and
+// silently consumes them - e.g. `"# test"` would otherwise render as just
+// `test`. Prepending a non-whitespace, invisible character forces the line
+// into a plain paragraph, and it's stripped back out of the first text event.
+const MARKUP_GUARD: char = '\u{200B}';
+
 fn markup_to_svg(markup: &str) -> String {
-	// The approach is simple - keep track of whether any of the formattings are activated.
-	// Then, whenever one is toggled we check if any were previously active.
-	// If they were, close the previous tspan. 
-	// Then, open a new tspan with the new formatting.
+	use pulldown_cmark::{Event, Options, Parser, Tag};
 
+	// As styles nest, combine the active ones into a single tspan, the same
+	// way the old toggle-based parser did, so `**bold *and italic***` still
+	// produces one tspan per distinct combination of active styles.
 	let mut in_bold = false;
-	let mut in_underline = false;
 	let mut in_italic = false;
-	let mut in_code = false;
+	let mut open_tspan = false;
+	let mut guard_stripped = false;
 
 	let mut output = String::new();
 
+	let guarded = format!("{}{}", MARKUP_GUARD, markup);
 
-
-	let mut escaped = false;
-
-	for c in markup.chars() {
-		if escaped {
-			output.push(c);
-			escaped = false;
-			continue;
-		}
-		match c {
-			'_' | '`' | '*' | '/' => {
-				if in_bold || in_underline || in_italic || in_code {
-					output += "</tspan>";
-				}
-				
-				match c {
-					'_' => in_underline = !in_underline,
-					'`' => in_code = !in_code,
-					'*' => in_bold = !in_bold,
-					'/' => in_italic = !in_italic,
-					_ => unreachable!(),
-				}
-
-				if in_bold || in_underline || in_italic || in_code {
-					output += &format!("<tspan class=\"{} {} {} {}\">",
-						if in_bold { "m_bold" } else { "" },
-						if in_underline { "m_underline" } else { "" },
-						if in_italic { "m_italic" } else { "" },
-						if in_code { "m_code" } else { "" });
-				}
+	for event in Parser::new_ext(&guarded, Options::empty()) {
+		match event {
+			Event::Start(Tag::Strong) => {
+				in_bold = true;
+				sync_style(&mut output, &mut open_tspan, in_bold, in_italic);
+			}
+			Event::End(Tag::Strong) => {
+				in_bold = false;
+				sync_style(&mut output, &mut open_tspan, in_bold, in_italic);
+			}
+			Event::Start(Tag::Emphasis) => {
+				in_italic = true;
+				sync_style(&mut output, &mut open_tspan, in_bold, in_italic);
 			}
-			'\\' => escaped = true,
-			x => output.push(x),
+			Event::End(Tag::Emphasis) => {
+				in_italic = false;
+				sync_style(&mut output, &mut open_tspan, in_bold, in_italic);
+			}
+			Event::Start(Tag::Link(_, url, _)) => {
+				output += &format!("<a href=\"{}\">", escape_attribute(&url));
+			}
+			Event::End(Tag::Link(..)) => {
+				output += "</a>";
+			}
+			Event::Code(text) => {
+				let text = strip_guard(&text, &mut guard_stripped);
+				output += &format!("<tspan class=\"m_code\">{}</tspan>", Escape(&text));
+			}
+			Event::Text(text) => {
+				let text = strip_guard(&text, &mut guard_stripped);
+				output += &format!("{}", Escape(&text));
+			}
+			Event::SoftBreak | Event::HardBreak => output.push(' '),
+			_ => {}
 		}
 	}
 
+	if open_tspan {
+		output += "</tspan>";
+	}
+
 	output
 }
 
+// Removes the `MARKUP_GUARD` character the first time it's seen - it always
+// appears at the very start of whichever text/code event comes first.
+fn strip_guard<'a>(text: &'a str, guard_stripped: &mut bool) -> std::borrow::Cow<'a, str> {
+	if !*guard_stripped {
+		*guard_stripped = true;
+		std::borrow::Cow::from(text.trim_start_matches(MARKUP_GUARD))
+	} else {
+		std::borrow::Cow::from(text)
+	}
+}
+
+// Escapes a link destination for use inside a double-quoted SVG attribute.
+// `Escape` only covers text-node content (`<`, `>`, `&`), so a literal `"` in
+// a bare link destination - which CommonMark permits - would otherwise break
+// out of the `href="..."` attribute and inject arbitrary markup.
+fn escape_attribute(url: &str) -> String {
+	format!("{}", Escape(&url)).replace('"', "&quot;")
+}
+
+// Closes the currently open combined-style tspan (if any) and, if any style
+// is still active, opens a new one reflecting `bold`/`italic`.
+fn sync_style(output: &mut String, open_tspan: &mut bool, bold: bool, italic: bool) {
+	if *open_tspan {
+		*output += "</tspan>";
+		*open_tspan = false;
+	}
+
+	if bold || italic {
+		*output += &format!("<tspan class=\"{} {}\">",
+			if bold { "m_bold" } else { "" },
+			if italic { "m_italic" } else { "" });
+		*open_tspan = true;
+	}
+}
+
 // Approach:
 //
 //  1. Read all data in from stdin.
@@ -281,30 +365,193 @@ fn markup_to_svg(markup: &str) -> String {
 //  ???
 //  4. Generate SVG.
 
+#[derive(Debug)]
 struct Lifetime {
 	starting_line: usize,
 	ending_line: usize,
 	comment: String,
 }
 
-fn run() -> Result<String, String> {
+// Either plain IO failure, or one or more malformed lifetime annotations.
+enum RunError {
+	Io(String),
+	Diagnostics(Vec<Diagnostic>),
+}
+
+fn run(theme: &Theme) -> Result<String, RunError> {
 	// Read all data in from stdin.
 	let mut buffer = String::new();
-	io::stdin().read_to_string(&mut buffer).map_err(|x| x.to_string())?;
+	io::stdin().read_to_string(&mut buffer).map_err(|x| RunError::Io(x.to_string()))?;
 
 	// Split into lines.
 	let mut code = buffer.lines().map(|x| x.into()).collect();
 
-	let lifetimes = find_lifetimes(&mut code);
+	let lifetimes = find_lifetimes(&mut code).map_err(RunError::Diagnostics)?;
 
-	let svg = generate_svg(&code, &lifetimes);
+	let svg = generate_svg(&code, &lifetimes, theme);
 
 	Ok(svg)
 }
 
+// Name used for the (stdin-only) input in diagnostic output.
+const INPUT_NAME: &str = "<stdin>";
+
 fn main() {
-	match run() {
+	let mut json_format = false;
+	let mut theme_name = "light".to_string();
+
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--format" => {
+				match args.next().as_deref() {
+					Some("json") => json_format = true,
+					Some("text") => json_format = false,
+					other => {
+						eprintln!("Error: --format expects \"text\" or \"json\", got {:?}", other);
+						std::process::exit(1);
+					}
+				}
+			}
+			"--theme" => {
+				match args.next() {
+					Some(name) => theme_name = name,
+					None => {
+						eprintln!("Error: --theme expects a preset name (\"light\", \"dark\") or a path to a theme file");
+						std::process::exit(1);
+					}
+				}
+			}
+			other => {
+				eprintln!("Error: unrecognized argument {:?}", other);
+				std::process::exit(1);
+			}
+		}
+	}
+
+	let theme = match Theme::load(&theme_name) {
+		Ok(theme) => theme,
+		Err(message) => {
+			eprintln!("Error: {}", message);
+			std::process::exit(1);
+		}
+	};
+
+	match run(&theme) {
 		Ok(s) => println!("{}", s),
-		Err(s) => println!("Error: {}", s),
+		Err(RunError::Io(s)) => {
+			eprintln!("Error: {}", s);
+			std::process::exit(1);
+		}
+		Err(RunError::Diagnostics(diagnostics)) => {
+			if json_format {
+				println!("{}", diagnostics::to_json(INPUT_NAME, &diagnostics));
+			} else {
+				diagnostics::print_human(INPUT_NAME, &diagnostics);
+			}
+			std::process::exit(1);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn markup_starting_with_heading_marker_keeps_the_hash() {
+		assert_eq!(markup_to_svg("# test"), "# test");
+	}
+
+	#[test]
+	fn markup_starting_with_dash_keeps_the_dash() {
+		assert_eq!(markup_to_svg("- dash note"), "- dash note");
+	}
+
+	#[test]
+	fn markup_starting_with_asterisk_is_not_swallowed() {
+		// A single leading `*` with no closing `*` isn't valid emphasis, so it
+		// should come through as plain text rather than being dropped as a
+		// bullet marker.
+		assert_eq!(markup_to_svg("* bullet-like note"), "* bullet-like note");
+	}
+
+	#[test]
+	fn markup_starting_with_ordered_list_marker_keeps_the_number() {
+		assert_eq!(markup_to_svg("1. first item"), "1. first item");
+	}
+
+	#[test]
+	fn markup_starting_with_blockquote_marker_keeps_the_angle_bracket() {
+		// Whether `>` is escaped to `&gt;` is an Escape implementation detail;
+		// what matters here is that the character survives at all.
+		let output = markup_to_svg("> quoted note");
+		assert!(output.starts_with('>') || output.starts_with("&gt;"));
+		assert!(output.ends_with("quoted note"));
+	}
+
+	#[test]
+	fn markup_emphasis_still_works() {
+		assert_eq!(markup_to_svg("**Hello** `world!`"),
+			"<tspan class=\"m_bold \">Hello</tspan> <tspan class=\"m_code\">world!</tspan>");
+	}
+
+	#[test]
+	fn markup_link_with_quote_in_url_cannot_break_out_of_the_attribute() {
+		let output = markup_to_svg(r#"[x](http://evil.com"onmouseover="alert(1))"#);
+		assert!(!output.contains("onmouseover=\""));
+		assert!(output.contains("&quot;"));
+	}
+
+	#[test]
+	fn well_formed_lifetime_is_ok() {
+		let mut code: Vec<String> = vec![
+			"let r;         // -------\\ Lifetime of `r`".to_string(),
+			"                // -------/".to_string(),
+		];
+
+		let lifetimes = find_lifetimes(&mut code).unwrap();
+
+		assert_eq!(lifetimes.len(), 1);
+		assert_eq!(lifetimes[0].starting_line, 0);
+		assert_eq!(lifetimes[0].ending_line, 1);
+		assert_eq!(lifetimes[0].comment, "Lifetime of `r`");
+	}
+
+	#[test]
+	fn restart_before_close_reports_secondary_span() {
+		let mut code: Vec<String> = vec![
+			"let r;         // -------\\ Lifetime of `r`".to_string(),
+			"let x = 5;     // -------\\ still open".to_string(),
+		];
+
+		let diagnostics = find_lifetimes(&mut code).unwrap_err();
+
+		assert_eq!(diagnostics.len(), 1);
+
+		let diagnostic = &diagnostics[0];
+		assert_eq!(diagnostic.span.line, 1);
+
+		let (secondary_span, _) = diagnostic.secondary.as_ref().unwrap();
+		assert_eq!(secondary_span.line, 0);
+
+		let expected_column = "let r;         // ".chars().count();
+		assert_eq!(secondary_span.column, expected_column);
+	}
+
+	#[test]
+	fn closing_without_opening_is_reported_with_no_secondary_span() {
+		let mut code: Vec<String> = vec![
+			"}              // -----/ unmatched".to_string(),
+		];
+
+		let diagnostics = find_lifetimes(&mut code).unwrap_err();
+
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].span.line, 0);
+		assert!(diagnostics[0].secondary.is_none());
+
+		let expected_column = "}              // ".chars().count();
+		assert_eq!(diagnostics[0].span.column, expected_column);
 	}
 }
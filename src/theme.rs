@@ -0,0 +1,251 @@
+// Color themes for the generated SVG: fonts, font sizes, line stroke,
+// annotation color and per-token syntax-highlighting colors. Selected via
+// `--theme`, either a built-in preset name or a path to a TOML/JSON file.
+
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TokenColors {
+	pub keyword: String,
+	pub ident: String,
+	pub string: String,
+	pub number: String,
+	pub comment: String,
+	pub lifetime: String,
+	pub op: String,
+	#[serde(rename = "macro")]
+	pub macro_: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Theme {
+	pub font_family: String,
+	pub code_font_size: u32,
+	pub annotation_font_size: u32,
+	pub background: String,
+	pub annotation_color: String,
+	pub line_color: String,
+	pub line_width: u32,
+	pub tokens: TokenColors,
+}
+
+impl Theme {
+	pub fn light() -> Theme {
+		Theme {
+			font_family: "monospace".to_string(),
+			code_font_size: 16,
+			annotation_font_size: 16,
+			background: "white".to_string(),
+			annotation_color: "black".to_string(),
+			line_color: "black".to_string(),
+			line_width: 2,
+			tokens: TokenColors {
+				keyword: "#8959a8".to_string(),
+				ident: "black".to_string(),
+				string: "#718c00".to_string(),
+				number: "#f5871f".to_string(),
+				comment: "#8e908c".to_string(),
+				lifetime: "#c82829".to_string(),
+				op: "#3e999f".to_string(),
+				macro_: "#4271ae".to_string(),
+			},
+		}
+	}
+
+	pub fn dark() -> Theme {
+		Theme {
+			font_family: "monospace".to_string(),
+			code_font_size: 16,
+			annotation_font_size: 16,
+			background: "#1d1f21".to_string(),
+			annotation_color: "#c5c8c6".to_string(),
+			line_color: "#c5c8c6".to_string(),
+			line_width: 2,
+			tokens: TokenColors {
+				keyword: "#b294bb".to_string(),
+				ident: "#c5c8c6".to_string(),
+				string: "#b5bd68".to_string(),
+				number: "#de935f".to_string(),
+				comment: "#969896".to_string(),
+				lifetime: "#cc6666".to_string(),
+				op: "#8abeb7".to_string(),
+				macro_: "#81a2be".to_string(),
+			},
+		}
+	}
+
+	/// Resolves `name` to a theme: a built-in preset name (`light`, `dark`),
+	/// or otherwise a path to a TOML/JSON theme file, the format inferred
+	/// from its extension (defaulting to TOML).
+	pub fn load(name: &str) -> Result<Theme, String> {
+		match name {
+			"light" => Ok(Theme::light()),
+			"dark" => Ok(Theme::dark()),
+			path => {
+				let contents = fs::read_to_string(path)
+					.map_err(|e| format!("couldn't read theme file '{}': {}", path, e))?;
+
+				if Path::new(path).extension().is_some_and(|ext| ext == "json") {
+					serde_json::from_str(&contents)
+						.map_err(|e| format!("invalid theme JSON in '{}': {}", path, e))
+				} else {
+					toml::from_str(&contents)
+						.map_err(|e| format!("invalid theme TOML in '{}': {}", path, e))
+				}
+			}
+		}
+	}
+
+	/// Builds the `<style>` CDATA block for this theme.
+	pub fn to_css(&self) -> String {
+		format!(r#"<![CDATA[
+
+.code {{
+	font-family: {font_family};
+	font-size: {code_font_size};
+	white-space: pre;
+	tab-size: 4;
+}}
+
+.annotation {{
+	font-size: {annotation_font_size};
+	fill: {annotation_color};
+}}
+
+.m_code {{
+	font-family: {font_family};
+}}
+
+.kw {{
+	fill: {kw};
+	font-weight: bold;
+}}
+
+.ident {{
+	fill: {ident};
+}}
+
+.string {{
+	fill: {string};
+}}
+
+.number {{
+	fill: {number};
+}}
+
+.comment {{
+	fill: {comment};
+	font-style: italic;
+}}
+
+.lifetime {{
+	fill: {lifetime};
+}}
+
+.op {{
+	fill: {op};
+}}
+
+.macro {{
+	fill: {macro_};
+}}
+
+.m_italic {{
+	font-style: italic;
+}}
+
+.m_bold {{
+	font-weight: bold;
+}}
+
+.background {{
+	fill: {background};
+}}
+
+.line {{
+	fill: none;
+	stroke: {line_color};
+	stroke-width: {line_width};
+	stroke-linecap: round;
+	stroke-linejoin: round;
+}}
+
+]]>"#,
+			font_family = self.font_family,
+			code_font_size = self.code_font_size,
+			annotation_font_size = self.annotation_font_size,
+			annotation_color = self.annotation_color,
+			background = self.background,
+			line_color = self.line_color,
+			line_width = self.line_width,
+			kw = self.tokens.keyword,
+			ident = self.tokens.ident,
+			string = self.tokens.string,
+			number = self.tokens.number,
+			comment = self.tokens.comment,
+			lifetime = self.tokens.lifetime,
+			op = self.tokens.op,
+			macro_ = self.tokens.macro_)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Writes `contents` to a fresh path under the OS temp dir (named after
+	// the test and the current PID, to avoid colliding with a parallel test
+	// run) and returns it.
+	fn write_temp_theme(name: &str, contents: &str) -> std::path::PathBuf {
+		let path = std::env::temp_dir().join(format!("lifetimediagrams-theme-test-{}-{}", std::process::id(), name));
+		fs::write(&path, contents).unwrap();
+		path
+	}
+
+	#[test]
+	fn load_light_preset() {
+		let theme = Theme::load("light").unwrap();
+		assert_eq!(theme.background, "white");
+	}
+
+	#[test]
+	fn load_dark_preset() {
+		let theme = Theme::load("dark").unwrap();
+		assert_eq!(theme.background, "#1d1f21");
+	}
+
+	#[test]
+	fn load_missing_path_is_an_error() {
+		let err = Theme::load("/no/such/theme/file.toml").unwrap_err();
+		assert!(err.contains("couldn't read theme file"));
+	}
+
+	#[test]
+	fn load_malformed_json_is_an_error() {
+		let path = write_temp_theme("bad.json", "{ not valid json");
+		let err = Theme::load(path.to_str().unwrap()).unwrap_err();
+		fs::remove_file(&path).unwrap();
+		assert!(err.contains("invalid theme JSON"));
+	}
+
+	#[test]
+	fn load_malformed_toml_is_an_error() {
+		let path = write_temp_theme("bad.toml", "this is not = valid [[[ toml");
+		let err = Theme::load(path.to_str().unwrap()).unwrap_err();
+		fs::remove_file(&path).unwrap();
+		assert!(err.contains("invalid theme TOML"));
+	}
+
+	#[test]
+	fn load_json_theme_file_uses_the_unprefixed_macro_key() {
+		let json = serde_json::to_string(&Theme::dark()).unwrap();
+		assert!(json.contains("\"macro\":"));
+		assert!(!json.contains("\"macro_\":"));
+
+		let path = write_temp_theme("roundtrip.json", &json);
+		let theme = Theme::load(path.to_str().unwrap()).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(theme.tokens.macro_, Theme::dark().tokens.macro_);
+	}
+}